@@ -1,6 +1,9 @@
 use ansi_term::Colour::Green;
 use ansi_term::Style;
-use chrono::{DateTime, Local, TimeZone, Utc};
+use std::io::BufRead;
+
+use chrono::{Datelike, DateTime, Duration, Local, NaiveDateTime, SecondsFormat, Timelike, TimeZone, Utc};
+use chrono_tz::Tz;
 use exitfailure::ExitFailure;
 use failure::Context;
 use failure::Fail;
@@ -13,7 +16,28 @@ arg_enum! {
     #[derive(Debug)]
     enum Fmt {
       RFC2822,
-      RFC3399,
+      RFC3339,
+    }
+}
+
+arg_enum! {
+    #[derive(Debug)]
+    enum Precision {
+      Secs,
+      Millis,
+      Micros,
+      Nanos,
+    }
+}
+
+impl Precision {
+    fn to_seconds_format(&self) -> SecondsFormat {
+        match self {
+            Precision::Secs => SecondsFormat::Secs,
+            Precision::Millis => SecondsFormat::Millis,
+            Precision::Micros => SecondsFormat::Micros,
+            Precision::Nanos => SecondsFormat::Nanos,
+        }
     }
 }
 
@@ -37,84 +61,442 @@ struct Cli {
     datetime: Option<DateTime<Utc>>,
     #[structopt(short, long, possible_values = & Fmt::variants(), case_insensitive = true)]
     output_fmt: Option<Fmt>,
+    /// Subsecond precision to use when `--output-fmt` is RFC3339.
+    #[structopt(long, possible_values = & Precision::variants(), case_insensitive = true, default_value = "Secs")]
+    precision: Precision,
+    /// IANA timezone name (e.g. America/New_York, Asia/Kolkata) to additionally render the result in.
+    #[structopt(short = "z", long, parse(try_from_str = parse_timezone))]
+    timezone: Option<Tz>,
+    /// Read one epoch or date per line from stdin and convert each, e.g. for piping bulk log timestamps.
+    #[structopt(long, conflicts_with_all(&["epoch", "datetime", "assume_local", "assume_utc"]))]
+    stdin: bool,
+    /// Datetime without a UTC offset in D-M-Y, M/D/Y or Y/M/D form (RFC 2822 and RFC 3339 are not accepted),
+    /// interpreted in the system's local timezone with DST resolved via libc's mktime.
+    #[structopt(long, conflicts_with_all(&["epoch", "datetime", "assume_utc"]), parse(try_from_str = parse_local_datetime))]
+    assume_local: Option<DateTime<Utc>>,
+    /// Datetime without a UTC offset in D-M-Y, M/D/Y or Y/M/D form (RFC 2822 and RFC 3339 are not accepted),
+    /// interpreted as UTC via libc's timegm.
+    #[structopt(long, conflicts_with_all(&["epoch", "datetime", "assume_local"]), parse(try_from_str = parse_utc_datetime))]
+    assume_utc: Option<DateTime<Utc>>,
+    /// Shift the resolved epoch forward by a span like `2d`, `36h`, `90m`, `1d12h`, or ISO 8601 `P1DT2H`.
+    #[structopt(long, conflicts_with("subtract"), parse(try_from_str = parse_duration))]
+    add: Option<Duration>,
+    /// Shift the resolved epoch backward by a span (see --add for the accepted syntax).
+    #[structopt(long, conflicts_with("add"), parse(try_from_str = parse_duration))]
+    subtract: Option<Duration>,
 }
 
 #[derive(Fail, Debug)]
-#[fail(display = "Missing timezone.")]
-struct MissingZone;
+#[fail(display = "Invalid timezone: {}", _0)]
+struct InvalidTimezone(String);
+
+fn parse_timezone(src: &str) -> Result<Tz, Context<String>> {
+    src.parse::<Tz>()
+        .map_err(|_| InvalidTimezone(src.to_string()))
+        .with_context(|_| format!("Could not parse timezone {}. Provide a valid IANA timezone name, e.g. America/New_York.", src))
+}
 
+#[derive(Fail, Debug)]
+#[fail(display = "timestamp {} is out of the representable range", _0)]
+struct OutOfRange(i64);
+
+/// Builds a `DateTime<Utc>`, reporting values chrono can't represent instead of panicking.
+fn utc_timestamp(input: i64, seconds: i64, nano_seconds: u32) -> Result<DateTime<Utc>, OutOfRange> {
+    Utc.timestamp_opt(seconds, nano_seconds).single().ok_or(OutOfRange(input))
+}
+
+/// Formats `parse_datetime` tries in order, newest/most-specific first.
+const DATE_FORMATS: &[&str] = &["RFC 3339", "RFC 2822", "%d-%m-%Y %T %z", "%m/%d/%Y %T %z", "%Y/%m/%d %T %z"];
+
+#[derive(Fail, Debug)]
+#[fail(display = "could not parse '{}' as any of: {}", input, attempted)]
+struct UnrecognizedDateFormat {
+    input: String,
+    attempted: String,
+}
+
+/// Cascades through RFC 3339, RFC 2822 and the legacy `D-M-Y`/`M/D/Y`/`Y/M/D` patterns.
 fn parse_datetime(src: &str) -> Result<DateTime<Utc>, Context<String>> {
     let processed_str = src.trim().replace("GMT", "+0000");
-    let re = Regex::new(r".*+\d{4}$").unwrap();
 
-    if !re.is_match(&processed_str) {
-        Err(MissingZone).with_context(|_| format!("Could not parse input {}. Provide valid timezone or GMT as suffix.", processed_str))
+    DateTime::parse_from_rfc3339(&processed_str)
+        .or_else(|_| DateTime::parse_from_rfc2822(&processed_str))
+        .or_else(|_| DateTime::parse_from_str(&processed_str, "%d-%m-%Y %T %z"))
+        .or_else(|_| DateTime::parse_from_str(&processed_str, "%m/%d/%Y %T %z"))
+        .or_else(|_| DateTime::parse_from_str(&processed_str, "%Y/%m/%d %T %z"))
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|_| UnrecognizedDateFormat {
+            input: processed_str.clone(),
+            attempted: DATE_FORMATS.join(", "),
+        })
+        .with_context(|_| format!("could not parse input: {}", processed_str))
+}
+
+/// Formats `parse_naive_datetime` tries in order: the strftime patterns of `DATE_FORMATS` minus
+/// the `%z` suffix. RFC 3339 and RFC 2822 are not tried.
+const NAIVE_DATE_FORMATS: &[&str] = &["%d-%m-%Y %T", "%m/%d/%Y %T", "%Y/%m/%d %T"];
+
+fn parse_naive_datetime(src: &str) -> Result<NaiveDateTime, Context<String>> {
+    let processed_str = src.trim();
+
+    NaiveDateTime::parse_from_str(processed_str, "%d-%m-%Y %T")
+        .or_else(|_| NaiveDateTime::parse_from_str(processed_str, "%m/%d/%Y %T"))
+        .or_else(|_| NaiveDateTime::parse_from_str(processed_str, "%Y/%m/%d %T"))
+        .map_err(|_| UnrecognizedDateFormat {
+            input: processed_str.to_string(),
+            attempted: NAIVE_DATE_FORMATS.join(", "),
+        })
+        .with_context(|_| format!("could not parse input: {}", processed_str))
+}
+
+/// Resolves a naive (zone-less) datetime to a Unix epoch via libc's `mktime` (local, DST-aware) or `timegm` (UTC).
+fn naive_to_epoch(naive: &NaiveDateTime, utc: bool) -> i64 {
+    let mut tm = libc::tm {
+        tm_sec: naive.second() as i32,
+        tm_min: naive.minute() as i32,
+        tm_hour: naive.hour() as i32,
+        tm_mday: naive.day() as i32,
+        tm_mon: naive.month() as i32 - 1,
+        tm_year: naive.year() - 1900,
+        tm_wday: 0,
+        tm_yday: 0,
+        tm_isdst: if utc { 0 } else { -1 },
+        tm_gmtoff: 0,
+        tm_zone: std::ptr::null(),
+    };
+
+    unsafe {
+        if utc {
+            libc::timegm(&mut tm)
+        } else {
+            libc::mktime(&mut tm)
+        }
+    }
+}
+
+fn parse_local_datetime(src: &str) -> Result<DateTime<Utc>, Context<String>> {
+    let naive = parse_naive_datetime(src)?;
+    let epoch = naive_to_epoch(&naive, false);
+    utc_timestamp(epoch, epoch, naive.nanosecond()).with_context(|_| format!("could not parse input: {}", src))
+}
+
+fn parse_utc_datetime(src: &str) -> Result<DateTime<Utc>, Context<String>> {
+    let naive = parse_naive_datetime(src)?;
+    let epoch = naive_to_epoch(&naive, true);
+    utc_timestamp(epoch, epoch, naive.nanosecond()).with_context(|_| format!("could not parse input: {}", src))
+}
+
+#[derive(Fail, Debug)]
+#[fail(display = "could not parse '{}' as a duration. Expected a span like '2d', '36h', '90m', '1d12h', or ISO 8601 'P1DT2H'.", _0)]
+struct InvalidDuration(String);
+
+/// Builds the `Duration` for a single unit-suffixed group, using checked arithmetic throughout
+/// so an in-range `value` that overflows once multiplied out (e.g. `i64::MAX` weeks) reports
+/// `None` instead of panicking inside chrono.
+fn unit_duration(value: i64, unit: &str) -> Option<Duration> {
+    match unit {
+        "w" => Duration::try_weeks(value),
+        "d" => Duration::try_days(value),
+        "h" => Duration::try_hours(value),
+        "m" => Duration::try_minutes(value),
+        "s" => Duration::try_seconds(value),
+        "ms" => Duration::try_milliseconds(value),
+        "us" => Some(Duration::microseconds(value)),
+        "ns" => Some(Duration::nanoseconds(value)),
+        _ => unreachable!(),
+    }
+}
+
+/// Scans unit-suffixed integer groups (`w`,`d`,`h`,`m`,`s`,`ms`,`us`,`ns`) like `1d12h` into a `Duration`.
+fn parse_unit_suffixed_duration(src: &str) -> Option<Duration> {
+    let re = Regex::new(r"(\d+)(ns|us|ms|w|d|h|m|s)").unwrap();
+    let mut total = Duration::zero();
+    let mut consumed = 0;
+
+    for cap in re.captures_iter(src) {
+        consumed += cap.get(0).unwrap().as_str().len();
+        let value: i64 = cap[1].parse().ok()?;
+        total = total.checked_add(&unit_duration(value, &cap[2])?)?;
+    }
+
+    if consumed == src.len() && consumed > 0 {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+/// Minimal ISO 8601 duration support (e.g. `P1DT2H`): `Y`/`W`/`D` before the `T`, `H`/`M`/`S` after.
+fn parse_iso8601_duration(src: &str) -> Option<Duration> {
+    let body = src.strip_prefix('P')?;
+    let (date_part, time_part) = match body.find('T') {
+        Some(idx) => (&body[..idx], &body[idx + 1..]),
+        None => (body, ""),
+    };
+
+    let date_re = Regex::new(r"(\d+)([YWD])").unwrap();
+    let time_re = Regex::new(r"(\d+)([HMS])").unwrap();
+    let mut total = Duration::zero();
+
+    let mut date_consumed = 0;
+    for cap in date_re.captures_iter(date_part) {
+        date_consumed += cap.get(0).unwrap().as_str().len();
+        let value: i64 = cap[1].parse().ok()?;
+        let unit = match &cap[2] {
+            "Y" => value.checked_mul(365).and_then(Duration::try_days),
+            "W" => Duration::try_weeks(value),
+            "D" => Duration::try_days(value),
+            _ => unreachable!(),
+        };
+        total = total.checked_add(&unit?)?;
+    }
+    if date_consumed != date_part.len() {
+        return None;
+    }
+
+    let mut time_consumed = 0;
+    for cap in time_re.captures_iter(time_part) {
+        time_consumed += cap.get(0).unwrap().as_str().len();
+        let value: i64 = cap[1].parse().ok()?;
+        let unit = match &cap[2] {
+            "H" => Duration::try_hours(value),
+            "M" => Duration::try_minutes(value),
+            "S" => Duration::try_seconds(value),
+            _ => unreachable!(),
+        };
+        total = total.checked_add(&unit?)?;
+    }
+    if time_consumed != time_part.len() {
+        return None;
+    }
+
+    if date_consumed + time_consumed == 0 {
+        return None;
+    }
+
+    Some(total)
+}
+
+fn parse_duration(src: &str) -> Result<Duration, InvalidDuration> {
+    let trimmed = src.trim();
+    let parsed = if trimmed.starts_with('P') {
+        parse_iso8601_duration(trimmed)
     } else {
-        DateTime::parse_from_rfc2822(&processed_str)
-            .or(DateTime::parse_from_str(&processed_str, "%d-%m-%Y %T %z")
-                .or(DateTime::parse_from_str(&processed_str, "%m/%d/%Y %T %z")
-                    .or(DateTime::parse_from_str(&processed_str, "%Y/%m/%d %T %z")
-                        .or(Err(MissingZone))
-                    )
-                )
-            )
-            .map(|dt| dt.with_timezone(&Utc))
-            .with_context(|_| format!("could not parse input: {}", processed_str))
+        parse_unit_suffixed_duration(trimmed)
+    };
+
+    parsed.ok_or_else(|| InvalidDuration(trimmed.to_string()))
+}
+
+/// Formats `datetime`, reporting `OutOfRange` rather than panicking when `fmt` is RFC2822 and the
+/// year falls outside chrono's representable `0..=9999` range for that format.
+fn format_datetime<Zone: TimeZone>(datetime: &DateTime<Zone>, fmt: &Fmt, precision: &Precision) -> Result<String, OutOfRange>
+where
+    Zone::Offset: std::fmt::Display,
+{
+    match fmt {
+        Fmt::RFC2822 => {
+            if (0..=9999).contains(&datetime.year()) {
+                Ok(datetime.to_rfc2822())
+            } else {
+                Err(OutOfRange(datetime.timestamp()))
+            }
+        }
+        Fmt::RFC3339 => Ok(datetime.to_rfc3339_opts(precision.to_seconds_format(), false)),
     }
 }
 
-fn display_results(datetime: &DateTime<Utc>) {
+/// Guesses the unit (seconds/millis/micros/nanos) of epoch `input` relative to current time `ts`
+/// (in seconds), returning the equivalent `(seconds, nanoseconds)` pair.
+fn resolve_epoch(input: i64, ts: i64) -> (i64, u32) {
+    let threshold: i64 = 10;
+    let milli_multiplier: i64 = 10i64.pow(3);
+    let micro_multiplier: i64 = 10i64.pow(6);
+    let nano_multiplier: i64 = 10i64.pow(9);
+
+    if input <= ts * threshold {
+        println!("Assuming that timestamp is in seconds.");
+        (input, 0)
+    } else if (input > ts * threshold) && (input <= ts * milli_multiplier * threshold) {
+        println!("Assuming that timestamp is in milliseconds.");
+        (input / milli_multiplier, (micro_multiplier * (input % milli_multiplier)) as u32)
+    } else if input > ts * milli_multiplier * threshold && input <= ts * micro_multiplier * threshold {
+        println!("Assuming that timestamp is in microseconds.");
+        (input / micro_multiplier, (milli_multiplier * (input % micro_multiplier)) as u32)
+    } else {
+        println!("Assuming that timestamp is in nanoseconds.");
+        (input / nano_multiplier, (input % nano_multiplier) as u32)
+    }
+}
+
+fn display_results(datetime: &DateTime<Utc>, fmt: &Fmt, precision: &Precision, timezone: &Option<Tz>) -> Result<(), OutOfRange> {
     println!("{}: {}", Style::new().fg(Green).bold().paint("Epoch timestamp"), datetime.timestamp());
     println!("Timestamp in milliseconds: {}", datetime.timestamp() * 1000 + datetime.timestamp_subsec_millis() as i64);
 
-    println!("{}: {:?}", Style::new().fg(Green).bold().paint("Date and time (GMT)"), datetime.to_rfc2822());
-    println!("Date and time (your time zone): {}", datetime.with_timezone(&Local).to_rfc2822());
+    println!("{}: {}", Style::new().fg(Green).bold().paint("Date and time (GMT)"), format_datetime(datetime, fmt, precision)?);
+    println!("Date and time (your time zone): {}", format_datetime(&datetime.with_timezone(&Local), fmt, precision)?);
+
+    if let Some(tz) = timezone {
+        println!("Date and time ({}): {}", tz, format_datetime(&datetime.with_timezone(tz), fmt, precision)?);
+    }
+    Ok(())
+}
+
+/// Displays `datetime`, then, if `--add`/`--subtract` was given, the shifted timestamp alongside it.
+/// Reports `OutOfRange` instead of panicking when shifting would overflow the representable range.
+fn display_with_offset(datetime: &DateTime<Utc>, fmt: &Fmt, precision: &Precision, timezone: &Option<Tz>, add: &Option<Duration>, subtract: &Option<Duration>) -> Result<(), OutOfRange> {
+    display_results(datetime, fmt, precision, timezone)?;
+
+    let shifted = if let Some(d) = add {
+        Some(datetime.checked_add_signed(*d).ok_or(OutOfRange(datetime.timestamp()))?)
+    } else if let Some(d) = subtract {
+        Some(datetime.checked_sub_signed(*d).ok_or(OutOfRange(datetime.timestamp()))?)
+    } else {
+        None
+    };
+
+    if let Some(shifted) = shifted {
+        println!();
+        println!("{}", Style::new().fg(Green).bold().paint("Shifted timestamp"));
+        display_results(&shifted, fmt, precision, timezone)?;
+    }
+    Ok(())
+}
+
+/// Converts a single stdin token (epoch or human date), reporting unparseable lines or
+/// out-of-range results to stderr instead of aborting the batch.
+fn convert_line(token: &str, fmt: &Fmt, precision: &Precision, timezone: &Option<Tz>, add: &Option<Duration>, subtract: &Option<Duration>) {
+    match token.parse::<i64>() {
+        Ok(input) => {
+            let ts = Utc::now().timestamp();
+            let (seconds, nano_seconds) = resolve_epoch(input, ts);
+            match utc_timestamp(input, seconds, nano_seconds) {
+                Ok(datetime) => {
+                    if let Err(e) = display_with_offset(&datetime, fmt, precision, timezone, add, subtract) {
+                        eprintln!("{}", e);
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        Err(_) => match parse_datetime(token) {
+            Ok(datetime) => {
+                if let Err(e) = display_with_offset(&datetime, fmt, precision, timezone, add, subtract) {
+                    eprintln!("{}", e);
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        },
+    }
 }
 
 fn main() -> Result<(), ExitFailure> {
     let args = Cli::from_args();
+    let fmt = args.output_fmt.unwrap_or(Fmt::RFC2822);
+
+    if args.stdin {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            let line = line.context("failed to read line from stdin")?;
+            let token = line.trim();
+            if !token.is_empty() {
+                convert_line(token, &fmt, &args.precision, &args.timezone, &args.add, &args.subtract);
+            }
+        }
+        return Ok(());
+    }
+
     let datetime: DateTime<Utc>;
+    let explicit_datetime = args.datetime.or(args.assume_local).or(args.assume_utc);
 
-    if let Some(datetime) = args.datetime {
-        display_results(&datetime);
+    if let Some(datetime) = explicit_datetime {
+        display_with_offset(&datetime, &fmt, &args.precision, &args.timezone, &args.add, &args.subtract)?;
     } else {
         let now = Utc::now();
 
-        if args.epoch.is_some() {
-            let ts = now.timestamp();
-
-            let input = args.epoch.unwrap();
-
-            let mut seconds: i64 = 0;
-            let mut nano_seconds: u32 = 0;
-
-            let threshold: i64 = 10;
-            let milli_multiplier: i64 = 10i64.pow(3);
-            let micro_multiplier: i64 = 10i64.pow(6);
-            let nano_multiplier: i64 = 10i64.pow(9);
-
-            if input <= ts * threshold {
-                println!("Assuming that timestamp is in seconds.");
-                seconds = input;
-            } else if (input > ts * threshold) && (input <= ts * milli_multiplier * threshold) {
-                println!("Assuming that timestamp is in milliseconds.");
-                seconds = input / milli_multiplier;
-                nano_seconds = (micro_multiplier * (input % milli_multiplier)) as u32;
-            } else if input > ts * milli_multiplier * threshold && input <= ts * micro_multiplier * threshold {
-                println!("Assuming that timestamp is in microseconds.");
-                seconds = input / micro_multiplier;
-                nano_seconds = (milli_multiplier * (input % micro_multiplier)) as u32;
-            } else if input > ts * micro_multiplier * threshold {
-                println!("Assuming that timestamp is in nanoseconds.");
-                seconds = ts / nano_multiplier;
-                nano_seconds = (ts % nano_multiplier) as u32;
-            }
-
-            datetime = Utc.timestamp(seconds, nano_seconds);
+        if let Some(input) = args.epoch {
+            let (seconds, nano_seconds) = resolve_epoch(input, now.timestamp());
+            datetime = utc_timestamp(input, seconds, nano_seconds)?;
         } else {
             datetime = now;
         }
-        display_results(&datetime);
+        display_with_offset(&datetime, &fmt, &args.precision, &args.timezone, &args.add, &args.subtract)?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `ts` stands in for "now" (in seconds) against which `resolve_epoch` auto-detects the unit.
+    const TS: i64 = 1_700_000_000;
+
+    #[test]
+    fn detects_seconds() {
+        let (seconds, nanos) = resolve_epoch(TS, TS);
+        assert_eq!(seconds, TS);
+        assert_eq!(nanos, 0);
+    }
+
+    #[test]
+    fn detects_millis() {
+        let input = TS * 1_000 + 1;
+        let (seconds, nanos) = resolve_epoch(input, TS);
+        assert_eq!(seconds, TS);
+        assert_eq!(nanos, 1_000_000);
+    }
+
+    #[test]
+    fn detects_micros() {
+        let input = TS * 1_000_000 + 1;
+        let (seconds, nanos) = resolve_epoch(input, TS);
+        assert_eq!(seconds, TS);
+        assert_eq!(nanos, 1_000);
+    }
+
+    #[test]
+    fn detects_nanos() {
+        let input = TS * 1_000_000_000 + 1;
+        let (seconds, nanos) = resolve_epoch(input, TS);
+        assert_eq!(seconds, TS);
+        assert_eq!(nanos, 1);
+    }
+
+    #[test]
+    fn rejects_out_of_range_seconds() {
+        assert!(utc_timestamp(i64::MIN, i64::MIN, 0).is_err());
+        assert!(utc_timestamp(TS, TS, 0).is_ok());
+    }
+
+    #[test]
+    fn parses_unit_suffixed_duration() {
+        let d = parse_duration("1d12h").unwrap();
+        assert_eq!(d, Duration::days(1) + Duration::hours(12));
+    }
+
+    #[test]
+    fn parses_iso8601_duration() {
+        let d = parse_duration("P1DT2H").unwrap();
+        assert_eq!(d, Duration::days(1) + Duration::hours(2));
+    }
+
+    #[test]
+    fn rejects_trailing_garbage() {
+        assert!(parse_duration("2dfoo").is_err());
+        assert!(parse_duration("P1Dfoo").is_err());
+    }
+
+    #[test]
+    fn rejects_empty_iso8601_duration() {
+        assert!(parse_duration("P").is_err());
+        assert!(parse_duration("PT").is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_duration_instead_of_panicking() {
+        assert!(parse_duration("99999999999999999999d").is_err());
+        assert!(parse_duration(&format!("{}w", i64::MAX)).is_err());
+        assert!(parse_duration(&format!("P{}Y", i64::MAX)).is_err());
+    }
+}